@@ -16,8 +16,46 @@
 //! trace.save("trace.dd").unwrap();
 //! ```
 //! [dragondance]: https://github.com/0ffffffffh/dragondance
+//!
+//! A previously written file can be read back with [`Trace::load`] or
+//! [`Trace::read`] for round-tripping, diffing, or merging coverage.
+
+use std::io::{BufRead, Error as IoError, Write};
 
-use std::io::{Error, Write};
+/// An error produced by the fallible constructors and mutators of this crate.
+///
+/// The panicking [`Module::new`] and [`Trace::add`] are thin wrappers over the
+/// fallible [`Module::try_new`] and [`Trace::try_add`]; a library fed untrusted
+/// address streams should prefer the `try_` variants so a single stray PC does
+/// not abort the whole process.
+#[derive(Debug)]
+pub enum Error {
+    /// A module's `base` was not strictly before its `end`.
+    ModuleRangeInvalid,
+    /// A module's span exceeds `u32::MAX` and is not representable in the format.
+    ModuleTooLarge,
+    /// An entry's size exceeds `u16::MAX` and is not representable in the format.
+    EntryTooLarge,
+    /// A program counter fell outside every known module.
+    PcNotMapped { pc: u64 },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::ModuleRangeInvalid => write!(f, "module `base` must be before `end`"),
+            Error::ModuleTooLarge => {
+                write!(f, "module sizes > u32::MAX are not representable in dragondance format")
+            }
+            Error::EntryTooLarge => {
+                write!(f, "entry size is too large for dragondance format, must be <= u16::MAX")
+            }
+            Error::PcNotMapped { pc } => write!(f, "no module found that contains PC {pc:#x}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
 
 /// A collection of code coverage entries that can be [exported][`Trace::write`] in a
 /// dragondance compatible format.
@@ -25,25 +63,40 @@ use std::io::{Error, Write};
 pub struct Trace {
     modules: Vec<Module>,
     entries: Vec<Entry>,
+    /// Module indices sorted by `base`, so a PC can be resolved with a binary
+    /// search instead of a linear scan. Rebuilt whenever `modules` changes.
+    index: Vec<usize>,
 }
 
 /// A named executable object
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Module {
-    name: &'static str,
+    name: String,
     base: u64,
     end: u64,
 }
 
 impl Module {
-    /// Create a new Module. Panics if end < base.
-    pub fn new(name: &'static str, base: u64, end: u64) -> Self {
-        assert!(base < end, "`base` must be before `end`");
-        assert!(
-            (end - base) <= u32::MAX as u64,
-            "Module sizes > u32::MAX are not representable in dragondance format"
-        );
-        Self { name, base, end }
+    /// Create a new Module, returning an [`Error`] if the range is invalid or
+    /// too large to represent.
+    pub fn try_new(name: impl Into<String>, base: u64, end: u64) -> Result<Self, Error> {
+        if base >= end {
+            return Err(Error::ModuleRangeInvalid);
+        }
+        if (end - base) > u32::MAX as u64 {
+            return Err(Error::ModuleTooLarge);
+        }
+        Ok(Self {
+            name: name.into(),
+            base,
+            end,
+        })
+    }
+
+    /// Create a new Module. Panics if `end <= base` or the span is too large;
+    /// see [`try_new`][`Module::try_new`] for the fallible version.
+    pub fn new(name: impl Into<String>, base: u64, end: u64) -> Self {
+        Self::try_new(name, base, end).expect("invalid Module")
     }
 
     /// True if the given pc is within this module.
@@ -55,46 +108,80 @@ impl Module {
 impl Trace {
     /// Create a new Trace by providing a slice of Modules.
     pub fn new(modules: &[Module]) -> Self {
+        let modules = modules.to_vec();
+        let index = Self::build_index(&modules);
         Self {
-            modules: modules.to_vec(),
+            modules,
             entries: Vec::new(),
+            index,
+        }
+    }
+
+    /// Add a module after construction, keeping existing 1-based ids stable and
+    /// rebuilding the sorted lookup index.
+    pub fn add_module(&mut self, module: Module) {
+        self.modules.push(module);
+        self.index = Self::build_index(&self.modules);
+    }
+
+    /// Build the `base`-sorted index of module positions.
+    fn build_index(modules: &[Module]) -> Vec<usize> {
+        let mut index: Vec<usize> = (0..modules.len()).collect();
+        index.sort_by_key(|&i| modules[i].base);
+        index
+    }
+
+    /// Resolve a PC to its module position via binary search: find the greatest
+    /// `base <= pc`, then confirm the PC is below that module's `end`.
+    fn lookup(&self, pc: u64) -> Option<usize> {
+        let pos = self.index.partition_point(|&i| self.modules[i].base <= pc);
+        if pos == 0 {
+            return None;
         }
+        let i = self.index[pos - 1];
+        self.modules[i].contains(pc).then_some(i)
     }
 
     /// Get the [`Module`] containing the given PC, or None.
-    pub fn module_containing<'a>(&'a self, pc: u64) -> Option<&'a Module> {
-        self.modules.iter().find(|m| m.contains(pc))
+    pub fn module_containing(&self, pc: u64) -> Option<&Module> {
+        self.lookup(pc).map(|i| &self.modules[i])
     }
 
     /// Add a coverage entry to the trace.
     ///
     /// * `pc`: The program counter executed
     /// * `size`: The length, in bytes, of the basic block. (Or instruction length if tracing
-    ///           single instructions)
+    ///   single instructions)
     pub fn add(&mut self, pc: u64, size: usize) {
-        let size = size
-            .try_into()
-            .expect("Entry size is too large for DragonDance file format, must be <= u16::MAX");
-
-        let entry = self
-            .modules
-            .iter()
-            .enumerate()
-            .find(|(_, m)| m.contains(pc))
-            .map(|(id, module)| Entry {
-                offset: (pc - module.base).try_into().unwrap(),
-                size,
-                module: id as u16 + 1,
-            })
-            .expect("No module found that contains PC");
+        self.try_add(pc, size).expect("failed to add coverage entry");
+    }
+
+    /// Add a coverage entry to the trace, returning an [`Error`] instead of
+    /// panicking when the entry is too large or the PC is not mapped.
+    ///
+    /// * `pc`: The program counter executed
+    /// * `size`: The length, in bytes, of the basic block. (Or instruction length if tracing
+    ///   single instructions)
+    pub fn try_add(&mut self, pc: u64, size: usize) -> Result<(), Error> {
+        let size = size.try_into().map_err(|_| Error::EntryTooLarge)?;
+
+        let id = self.lookup(pc).ok_or(Error::PcNotMapped { pc })?;
+        let module = &self.modules[id];
+        let entry = Entry {
+            offset: (pc - module.base).try_into().unwrap(),
+            size,
+            module: id as u16 + 1,
+            inst_count: 0,
+        };
 
         self.entries.push(entry);
+        Ok(())
     }
 
     /// Write the coverage trace in the [Dragondance Pintool Helper format].
     ///
     /// [Dragondance Pintool Helper format]: https://github.com/0ffffffffh/dragondance/issues/1#issuecomment-493699908
-    pub fn write(&self, writer: &mut impl Write) -> Result<(), Error> {
+    pub fn write(&self, writer: &mut impl Write) -> Result<(), IoError> {
         // write header
         writeln!(writer, "DDPH-PINTOOL")?;
         writeln!(
@@ -106,8 +193,9 @@ impl Trace {
 
         // write module table
         writeln!(writer, "MODULE_TABLE")?;
-        for (number, Module { name, base, end }) in self.modules.iter().enumerate() {
+        for (number, module) in self.modules.iter().enumerate() {
             let number = number + 1;
+            let Module { name, base, end } = module;
             writeln!(writer, "{number}, {base:#x}, {end:#x}, {name}")?;
         }
 
@@ -121,12 +209,309 @@ impl Trace {
     }
 
     /// Save the coverage trace to a file.
-    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), Error> {
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), IoError> {
         let mut outfile = std::fs::File::create(path)?;
         self.write(&mut outfile)
     }
+
+    /// Read a coverage trace written by [`write`][`Trace::write`] back into memory.
+    ///
+    /// The `DDPH-PINTOOL` magic line is verified, the `EntryCount`/`ModuleCount`
+    /// header is parsed, the `MODULE_TABLE` rows are decoded, and finally the
+    /// fixed 12-byte `ENTRY_TABLE` records are read with [`read_exact`]. Parse
+    /// failures are reported through [`ParseError`] rather than by panicking, so
+    /// callers feeding untrusted files can recover.
+    ///
+    /// An entry's absolute program counter is recoverable by adding its `offset`
+    /// to the `base` of the module referenced by `module`.
+    ///
+    /// [`read_exact`]: std::io::Read::read_exact
+    pub fn read(reader: &mut impl BufRead) -> Result<Trace, ParseError> {
+        let mut line = String::new();
+        let mut lineno = 0usize;
+
+        // Read one text line, tracking the line number and mapping an
+        // invalid-UTF-8 decode onto `Utf8Error`.
+        fn next_line(
+            reader: &mut dyn BufRead,
+            buf: &mut String,
+            lineno: &mut usize,
+        ) -> Result<Option<String>, ParseError> {
+            buf.clear();
+            match reader.read_line(buf) {
+                Ok(0) => Ok(None),
+                Ok(_) => {
+                    *lineno += 1;
+                    Ok(Some(buf.trim_end_matches(['\r', '\n']).to_owned()))
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::InvalidData => Err(ParseError::Utf8Error),
+                Err(e) => Err(ParseError::Io(e)),
+            }
+        }
+
+        // magic
+        match next_line(reader, &mut line, &mut lineno)? {
+            Some(ref l) if l == "DDPH-PINTOOL" => {}
+            _ => return Err(ParseError::BadMagic),
+        }
+
+        // EntryCount: N, ModuleCount: M
+        let header = next_line(reader, &mut line, &mut lineno)?
+            .ok_or(ParseError::MalformedHeader { line: lineno + 1 })?;
+        let (entry_count, module_count) =
+            parse_counts(&header).ok_or(ParseError::MalformedHeader { line: lineno })?;
+
+        // MODULE_TABLE
+        match next_line(reader, &mut line, &mut lineno)? {
+            Some(ref l) if l == "MODULE_TABLE" => {}
+            _ => return Err(ParseError::MalformedHeader { line: lineno }),
+        }
+
+        let mut modules = Vec::new();
+        for _ in 0..module_count {
+            let row = match next_line(reader, &mut line, &mut lineno)? {
+                Some(row) => row,
+                None => {
+                    return Err(ParseError::CountMismatch {
+                        declared: module_count,
+                        actual: modules.len(),
+                    })
+                }
+            };
+            modules.push(parse_module_row(&row).ok_or(ParseError::InvalidModuleRow { line: lineno })?);
+        }
+
+        // ENTRY_TABLE
+        match next_line(reader, &mut line, &mut lineno)? {
+            Some(ref l) if l == "ENTRY_TABLE" => {}
+            _ => return Err(ParseError::MalformedHeader { line: lineno }),
+        }
+
+        // Fixed 12-byte binary records follow.
+        let mut entries = Vec::new();
+        for i in 0..entry_count {
+            let mut buf = [0u8; 12];
+            match reader.read_exact(&mut buf) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Err(ParseError::Truncated {
+                        offset: (i * 12) as u64,
+                    })
+                }
+                Err(e) => return Err(ParseError::Io(e)),
+            }
+            entries.push(Entry::from_le_bytes(&buf));
+        }
+
+        let index = Self::build_index(&modules);
+        Ok(Trace {
+            modules,
+            entries,
+            index,
+        })
+    }
+
+    /// Check the trace for internal inconsistencies before trusting it for a
+    /// write.
+    ///
+    /// Returns `Ok(())` when the trace is sound, otherwise every problem found
+    /// is collected into a [`ValidationIssue`] list. The checks mirror the
+    /// invariants the on-disk format relies on:
+    ///
+    /// * modules whose `[base, end)` ranges overlap, which makes
+    ///   [`module_containing`][`Trace::module_containing`] ambiguous since it
+    ///   only checks the module with the greatest `base <= pc`, so a PC inside
+    ///   an overlapping module can resolve to the other module or to none,
+    /// * entries whose `offset + size` runs past the end of their module,
+    /// * duplicate basic blocks (same module and offset), and
+    /// * module or entry counts that would overflow the `u16`/`u32` fields
+    ///   written to disk.
+    pub fn validate(&self) -> Result<(), Vec<ValidationIssue>> {
+        let mut issues = Vec::new();
+
+        // Overlapping module ranges. Module numbers are reported 1-based to
+        // match the emitted MODULE_TABLE.
+        for i in 0..self.modules.len() {
+            for j in (i + 1)..self.modules.len() {
+                let a = &self.modules[i];
+                let b = &self.modules[j];
+                if a.base < b.end && b.base < a.end {
+                    issues.push(ValidationIssue::ModuleOverlap {
+                        first: i + 1,
+                        second: j + 1,
+                    });
+                }
+            }
+        }
+
+        // Counts that cannot be represented once written.
+        if self.modules.len() > u16::MAX as usize {
+            issues.push(ValidationIssue::TooManyModules {
+                count: self.modules.len(),
+            });
+        }
+        if self.entries.len() > u32::MAX as usize {
+            issues.push(ValidationIssue::TooManyEntries {
+                count: self.entries.len(),
+            });
+        }
+
+        // Per-entry bounds and duplicate detection.
+        let mut seen = std::collections::HashMap::new();
+        for (idx, e) in self.entries.iter().enumerate() {
+            if let Some(module) = (e.module as usize)
+                .checked_sub(1)
+                .and_then(|i| self.modules.get(i))
+            {
+                let span = module.end - module.base;
+                if e.offset as u64 + e.size as u64 > span {
+                    issues.push(ValidationIssue::EntryOutOfBounds { entry: idx });
+                }
+            }
+
+            if let Some(first) = seen.insert((e.module, e.offset), idx) {
+                issues.push(ValidationIssue::DuplicateEntry {
+                    first,
+                    duplicate: idx,
+                });
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(issues)
+        }
+    }
+
+    /// Load a coverage trace from a file written by [`save`][`Trace::save`].
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Trace, ParseError> {
+        let file = std::fs::File::open(path)?;
+        let mut reader = std::io::BufReader::new(file);
+        Self::read(&mut reader)
+    }
+}
+
+/// Parse the `EntryCount: N, ModuleCount: M` header line.
+fn parse_counts(line: &str) -> Option<(usize, usize)> {
+    let rest = line.strip_prefix("EntryCount: ")?;
+    let (entries, rest) = rest.split_once(", ModuleCount: ")?;
+    Some((entries.trim().parse().ok()?, rest.trim().parse().ok()?))
+}
+
+/// Parse a single `MODULE_TABLE` row: `number, base, end, name`.
+fn parse_module_row(row: &str) -> Option<Module> {
+    let mut fields = row.splitn(4, ", ");
+    let _number: u64 = fields.next()?.trim().parse().ok()?;
+    let base = parse_hex(fields.next()?.trim())?;
+    let end = parse_hex(fields.next()?.trim())?;
+    let name = fields.next()?.to_owned();
+    if base >= end {
+        return None;
+    }
+    Some(Module { name, base, end })
+}
+
+/// Parse a `0x`-prefixed (or bare) hexadecimal u64.
+fn parse_hex(s: &str) -> Option<u64> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    u64::from_str_radix(s, 16).ok()
+}
+
+/// An error encountered while [reading][`Trace::read`] a dragondance file.
+#[derive(Debug)]
+pub enum ParseError {
+    /// The `DDPH-PINTOOL` magic line was missing or wrong.
+    BadMagic,
+    /// A structural header line (`EntryCount`/`ModuleCount`, `MODULE_TABLE`,
+    /// or `ENTRY_TABLE`) was missing or malformed.
+    MalformedHeader { line: usize },
+    /// A `MODULE_TABLE` row could not be parsed.
+    InvalidModuleRow { line: usize },
+    /// The binary `ENTRY_TABLE` ended before the declared number of entries,
+    /// at the given byte offset into the entry table.
+    Truncated { offset: u64 },
+    /// The input ended before all `MODULE_TABLE` rows declared in the header
+    /// were read; `actual` is the number of rows that were present.
+    CountMismatch { declared: usize, actual: usize },
+    /// A text line (such as a module row and its name) was not valid UTF-8.
+    Utf8Error,
+    /// An underlying I/O error occurred.
+    Io(IoError),
+}
+
+impl From<IoError> for ParseError {
+    fn from(e: IoError) -> Self {
+        ParseError::Io(e)
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::BadMagic => write!(f, "missing or invalid DDPH-PINTOOL magic"),
+            ParseError::MalformedHeader { line } => write!(f, "malformed header on line {line}"),
+            ParseError::InvalidModuleRow { line } => write!(f, "invalid module row on line {line}"),
+            ParseError::Truncated { offset } => {
+                write!(f, "entry table truncated at byte offset {offset}")
+            }
+            ParseError::CountMismatch { declared, actual } => {
+                write!(f, "declared {declared} rows but found {actual}")
+            }
+            ParseError::Utf8Error => write!(f, "text line was not valid UTF-8"),
+            ParseError::Io(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A single problem reported by [`Trace::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationIssue {
+    /// Two modules (given by their 1-based MODULE_TABLE numbers) overlap.
+    ModuleOverlap { first: usize, second: usize },
+    /// The entry at this index extends past the end of its module.
+    EntryOutOfBounds { entry: usize },
+    /// A basic block (same module and offset) appears more than once.
+    DuplicateEntry { first: usize, duplicate: usize },
+    /// There are more modules than the `u16` module field can reference.
+    TooManyModules { count: usize },
+    /// There are more entries than the `u32` count field can represent.
+    TooManyEntries { count: usize },
+}
+
+impl std::fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationIssue::ModuleOverlap { first, second } => {
+                write!(f, "modules {first} and {second} have overlapping ranges")
+            }
+            ValidationIssue::EntryOutOfBounds { entry } => {
+                write!(f, "entry {entry} extends past the end of its module")
+            }
+            ValidationIssue::DuplicateEntry { first, duplicate } => {
+                write!(f, "entry {duplicate} duplicates basic block of entry {first}")
+            }
+            ValidationIssue::TooManyModules { count } => {
+                write!(f, "{count} modules exceed the u16 module field")
+            }
+            ValidationIssue::TooManyEntries { count } => {
+                write!(f, "{count} entries exceed the u32 count field")
+            }
+        }
+    }
 }
 
+impl std::error::Error for ValidationIssue {}
+
 // A single coverage "event". Likely this will represent a single basic block of assembly that was
 // executed.
 #[derive(Debug, Clone, Copy)]
@@ -134,22 +519,33 @@ struct Entry {
     offset: u32,
     size: u16,
     module: u16,
+    inst_count: u32,
 }
 
 impl Entry {
-    fn write(&self, w: &mut impl Write) -> Result<(), Error> {
+    fn write(&self, w: &mut impl Write) -> Result<(), IoError> {
         let mut buf = [0; 12];
 
-        buf[0..4].copy_from_slice(&self.offset.to_ne_bytes());
-        buf[4..6].copy_from_slice(&self.size.to_ne_bytes());
-        buf[6..8].copy_from_slice(&self.module.to_ne_bytes());
-
-        // instruction count is not used by dragon dance at all, and it is unclear what it is
-        let inst_count: u32 = 0;
-        buf[8..12].copy_from_slice(&inst_count.to_ne_bytes());
+        // dragondance reads these fields with a fixed byte order, so the wire
+        // layout is always little-endian, independent of the host architecture.
+        buf[0..4].copy_from_slice(&self.offset.to_le_bytes());
+        buf[4..6].copy_from_slice(&self.size.to_le_bytes());
+        buf[6..8].copy_from_slice(&self.module.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.inst_count.to_le_bytes());
 
         w.write_all(&buf)
     }
+
+    // instruction count is not used by dragon dance at all, and it is unclear what it is, but it
+    // is preserved so a written trace round-trips exactly.
+    fn from_le_bytes(buf: &[u8; 12]) -> Self {
+        Self {
+            offset: u32::from_le_bytes(buf[0..4].try_into().unwrap()),
+            size: u16::from_le_bytes(buf[4..6].try_into().unwrap()),
+            module: u16::from_le_bytes(buf[6..8].try_into().unwrap()),
+            inst_count: u32::from_le_bytes(buf[8..12].try_into().unwrap()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -176,4 +572,117 @@ mod tests {
         let mut trace = Trace::new(&modules);
         trace.add(0xdead, 10);
     }
+
+    #[test]
+    fn read_round_trip() {
+        let modules = [
+            Module::new("abcd.so", 0x1000, 0x2000),
+            Module::new("libc.so", 0x555000, 0x556000),
+        ];
+        let mut trace = Trace::new(&modules);
+        trace.add(0x1234, 1);
+        trace.add(0x555010, 7);
+
+        let mut out = Vec::new();
+        trace.write(&mut out).unwrap();
+
+        let parsed = Trace::read(&mut out.as_slice()).unwrap();
+        assert_eq!(parsed.modules.len(), 2);
+        assert_eq!(parsed.modules[1].name, "libc.so");
+        assert_eq!(parsed.entries.len(), 2);
+
+        // absolute PC is recoverable from offset + module base
+        let e = parsed.entries[0];
+        let base = parsed.modules[e.module as usize - 1].base;
+        assert_eq!(base + e.offset as u64, 0x1234);
+    }
+
+    #[test]
+    fn try_add_reports_unmapped() {
+        let modules = [Module::new("abcd.so", 0x1000, 0x2000)];
+        let mut trace = Trace::new(&modules);
+        assert!(matches!(
+            trace.try_add(0xdead, 1),
+            Err(Error::PcNotMapped { pc: 0xdead })
+        ));
+    }
+
+    #[test]
+    fn try_new_rejects_bad_range() {
+        assert!(matches!(
+            Module::try_new("x", 0x2000, 0x1000),
+            Err(Error::ModuleRangeInvalid)
+        ));
+    }
+
+    #[test]
+    fn entry_is_little_endian() {
+        // A known entry must serialize to the same bytes regardless of the
+        // target's native endianness.
+        let entry = Entry {
+            offset: 0x0403_0201,
+            size: 0x0605,
+            module: 0x0807,
+            inst_count: 0x0c0b_0a09,
+        };
+        let mut out = Vec::new();
+        entry.write(&mut out).unwrap();
+        assert_eq!(
+            out,
+            [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c]
+        );
+
+        // ...and round-trips back to the same fields.
+        let buf: [u8; 12] = out.try_into().unwrap();
+        let back = Entry::from_le_bytes(&buf);
+        assert_eq!(back.offset, entry.offset);
+        assert_eq!(back.size, entry.size);
+        assert_eq!(back.module, entry.module);
+        assert_eq!(back.inst_count, entry.inst_count);
+    }
+
+    #[test]
+    fn lookup_finds_correct_module_after_add_module() {
+        let modules = [Module::new("a", 0x5000, 0x6000)];
+        let mut trace = Trace::new(&modules);
+        // add a lower module after construction; the index must be rebuilt so
+        // the binary search still finds it, and ids stay stable.
+        trace.add_module(Module::new("b", 0x1000, 0x2000));
+
+        assert_eq!(trace.module_containing(0x1100).unwrap().name, "b");
+        assert_eq!(trace.module_containing(0x5100).unwrap().name, "a");
+        assert!(trace.module_containing(0x3000).is_none());
+
+        trace.add(0x1100, 2);
+        // "b" was appended, so it keeps id 2.
+        assert_eq!(trace.entries[0].module, 2);
+    }
+
+    #[test]
+    fn validate_detects_overlap() {
+        let modules = [
+            Module::new("a", 0x1000, 0x3000),
+            Module::new("b", 0x2000, 0x4000),
+        ];
+        let trace = Trace::new(&modules);
+        let issues = trace.validate().unwrap_err();
+        assert!(issues.contains(&ValidationIssue::ModuleOverlap {
+            first: 1,
+            second: 2
+        }));
+    }
+
+    #[test]
+    fn validate_clean_trace() {
+        let modules = [Module::new("a", 0x1000, 0x2000)];
+        let mut trace = Trace::new(&modules);
+        trace.add(0x1100, 4);
+        assert!(trace.validate().is_ok());
+    }
+
+    #[test]
+    fn read_bad_magic() {
+        let mut bytes = b"NOPE\n".as_slice();
+        assert!(matches!(Trace::read(&mut bytes), Err(ParseError::BadMagic)));
+    }
 }